@@ -1,5 +1,6 @@
 use std::error::Error;
 
+use encoding_rs::Encoding;
 use oxc::{
 	allocator::{Allocator, StringBuilder},
 	span::Span,
@@ -12,6 +13,230 @@ use crate::{
 	rule::{RewriteRule, RewriteRuleCallback},
 };
 
+/// How many leading bytes of the document we're willing to prescan for a `<meta charset>`
+/// declaration, mirroring the cap browsers use so a huge `<head>` can't make sniffing expensive.
+const META_PRESCAN_LIMIT: usize = 1024;
+
+/// Sniffs the character encoding of an HTML document the way browsers do it: a BOM takes
+/// priority, then a `<meta charset>`/`http-equiv` prescan of the first [`META_PRESCAN_LIMIT`]
+/// bytes, and finally the HTML spec's `windows-1252` default.
+///
+/// This only decides *how to decode*; it never fails; unrecognised or missing labels simply
+/// fall through to the next step.
+pub fn detect_encoding(bytes: &[u8]) -> &'static Encoding {
+	sniff_encoding(bytes).0
+}
+
+/// Does the actual work behind [`detect_encoding`], additionally returning the length of the BOM
+/// that led to the decision (`0` if none was found), so [`decode_document`] knows how many bytes
+/// to skip before decoding — a BOM indicates the encoding but isn't itself part of the content.
+fn sniff_encoding(bytes: &[u8]) -> (&'static Encoding, usize) {
+	if let Some((encoding, bom_len)) = Encoding::for_bom(bytes) {
+		return (encoding, bom_len);
+	}
+
+	let prescan = &bytes[..bytes.len().min(META_PRESCAN_LIMIT)];
+	if let Some(label) = find_meta_charset_label(prescan)
+		&& let Some(encoding) = Encoding::for_label(label.as_bytes())
+	{
+		return (encoding, 0);
+	}
+
+	(encoding_rs::WINDOWS_1252, 0)
+}
+
+/// Finds the byte offset of the first occurrence of `needle` in `haystack`, if any.
+fn find_subsequence(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+	haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+/// Finds the charset label out of a `<meta charset="...">` or
+/// `<meta http-equiv="content-type" content="...; charset=...">` declaration in `bytes`, if any.
+///
+/// This is a byte-level scan, not a real parse, but it's restricted to text actually inside a
+/// `<meta ...>` tag, so a `charset=` substring in a script or comment ahead of the real
+/// declaration can't be mistaken for it.
+fn find_meta_charset_label(bytes: &[u8]) -> Option<&str> {
+	let lower = bytes.to_ascii_lowercase();
+	let mut pos = 0;
+
+	while let Some(rel) = find_subsequence(&lower[pos..], b"<meta") {
+		let tag_start = pos + rel;
+		let after_name = tag_start + "<meta".len();
+
+		let is_meta_tag = lower
+			.get(after_name)
+			.is_none_or(|&b| b.is_ascii_whitespace() || b == b'>' || b == b'/');
+
+		if !is_meta_tag {
+			pos = after_name;
+			continue;
+		}
+
+		let tag_end = lower[tag_start..]
+			.iter()
+			.position(|&b| b == b'>')
+			.map_or(lower.len(), |i| tag_start + i);
+
+		if let Some(label) = find_charset_in_tag(&lower, bytes, tag_start, tag_end) {
+			return Some(label);
+		}
+
+		pos = tag_end.max(after_name);
+	}
+
+	None
+}
+
+/// Finds a `charset=` marker within `lower[start..end]` (the lowercased form of `bytes`, used
+/// only to locate the marker case-insensitively) and reads the label that follows it out of
+/// `bytes`, preserving the label's original case.
+fn find_charset_in_tag<'a>(
+	lower: &[u8],
+	bytes: &'a [u8],
+	start: usize,
+	end: usize,
+) -> Option<&'a str> {
+	let marker = b"charset=";
+	let rel = find_subsequence(&lower[start..end], marker)?;
+	let rest = &bytes[start + rel + marker.len()..end];
+
+	let (quote, rest) = match rest.first() {
+		Some(b'"') => (Some(b'"'), &rest[1..]),
+		Some(b'\'') => (Some(b'\''), &rest[1..]),
+		_ => (None, rest),
+	};
+
+	let value_end = match quote {
+		Some(quote) => rest.iter().position(|&b| b == quote)?,
+		None => rest
+			.iter()
+			.position(|&b| b.is_ascii_whitespace() || b == b'"' || b == b'\'' || b == b'>')
+			.unwrap_or(rest.len()),
+	};
+
+	std::str::from_utf8(&rest[..value_end]).ok()
+}
+
+/// Decodes a raw HTML document into UTF-8, detecting its source encoding via [`detect_encoding`].
+///
+/// This is the front door callers should use instead of assuming the input is already UTF-8:
+/// [`Visitor::data`] and every `calculate_bounds`/`boundaries` offset are computed against the
+/// *decoded* buffer, so decoding must happen once, up front, before a [`Visitor`] is ever built.
+/// The returned encoding lets the caller re-encode the (rewritten, UTF-8) output back to the
+/// page's original charset if it needs to.
+pub fn decode_document(bytes: &[u8]) -> (String, &'static Encoding) {
+	let (encoding, bom_len) = sniff_encoding(bytes);
+	let (decoded, _encoding, _had_errors) = encoding.decode(&bytes[bom_len..]);
+	(decoded.into_owned(), encoding)
+}
+
+/// Rewrites the `charset=` parameter of a `content-type` `http-equiv` `content` value to
+/// `utf-8`, leaving the rest of the value (e.g. `text/html;`) untouched. Returns `None` if
+/// `content` doesn't carry a `charset` parameter, since there's nothing to rewrite.
+fn rewrite_charset_param<'alloc>(alloc: &'alloc Allocator, content: &str) -> Option<&'alloc str> {
+	let lower = content.to_ascii_lowercase();
+	let marker = "charset=";
+	let idx = lower.find(marker)?;
+
+	let before = &content[..idx];
+	let rest = &content[idx + marker.len()..];
+
+	let (quote, rest) = match rest.as_bytes().first() {
+		Some(b'"') => ("\"", &rest[1..]),
+		Some(b'\'') => ("'", &rest[1..]),
+		_ => ("", rest),
+	};
+
+	let value_len = if quote.is_empty() {
+		rest.find(|c: char| c.is_whitespace() || c == ';')
+			.unwrap_or(rest.len())
+	} else {
+		rest.find(quote).unwrap_or(rest.len())
+	};
+
+	let after = &rest[value_len..];
+
+	Some(alloc.alloc_str(&format!("{before}{marker}{quote}utf-8{quote}{after}")))
+}
+
+/// Parses a `srcset`/`imagesrcset` value into `(url, descriptor)` candidates, following the
+/// WHATWG "parsing a srcset attribute" algorithm closely enough for our purposes: a candidate's
+/// URL is collected as a single whitespace-delimited token, so a comma embedded inside it (as in
+/// a `data:` URL, which is never followed by whitespace mid-URL) is never mistaken for a
+/// separator. A candidate only ends at a comma that trails the URL token directly (no
+/// whitespace in between) or at the next comma after its descriptor.
+fn parse_srcset_candidates(value: &str) -> Vec<(&str, &str)> {
+	let mut candidates = Vec::new();
+	let mut rest = value;
+
+	loop {
+		rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace() || c == ',');
+		if rest.is_empty() {
+			break;
+		}
+
+		let url_end = rest
+			.find(|c: char| c.is_ascii_whitespace())
+			.unwrap_or(rest.len());
+		let url = &rest[..url_end];
+		rest = &rest[url_end..];
+
+		let trimmed_url = url.trim_end_matches(',');
+		if trimmed_url.len() != url.len() {
+			// The URL token ended in one or more commas with no whitespace before them -
+			// that's a separator glued to the URL, not part of it, so the candidate ends here
+			// with no descriptor.
+			candidates.push((trimmed_url, ""));
+			continue;
+		}
+
+		rest = rest.trim_start_matches(|c: char| c.is_ascii_whitespace());
+
+		let descriptor_end = rest.find(',').unwrap_or(rest.len());
+		let descriptor = rest[..descriptor_end].trim_end_matches(|c: char| c.is_ascii_whitespace());
+		rest = &rest[descriptor_end..];
+
+		candidates.push((url, descriptor));
+	}
+
+	candidates
+}
+
+/// Rewrites every URL in a `srcset`/`imagesrcset` value through `cb`, preserving each
+/// candidate's descriptor (`2x`, `640w`, ...) untouched, and rejoins the result with `, `.
+///
+/// Matches this file's convention for rule-governed attributes (see the `remove_attr` branch in
+/// [`Visitor::rewrite`]): if `cb` returns `None` for any candidate's URL, that URL must not reach
+/// the client unproxied, so the whole attribute is dropped (`Ok(None)`) rather than falling back
+/// to any raw URL.
+fn rewrite_srcset<'alloc, T>(
+	alloc: &'alloc Allocator,
+	value: &str,
+	cb: &RewriteRuleCallback<T>,
+	rule_data: &T,
+) -> Result<Option<&'alloc str>, Box<dyn Error + Sync + Send>> {
+	let mut out = String::new();
+
+	for (url, descriptor) in parse_srcset_candidates(value) {
+		let Some(rewritten) = cb(alloc, url, rule_data)? else {
+			return Ok(None);
+		};
+
+		if !out.is_empty() {
+			out.push_str(", ");
+		}
+
+		out.push_str(rewritten);
+		if !descriptor.is_empty() {
+			out.push(' ');
+			out.push_str(descriptor);
+		}
+	}
+
+	Ok(Some(alloc.alloc_str(&out)))
+}
+
 const EVENT_ATTRIBUTES: [&str; 100] = [
 	"onbeforexrselect",
 	"onabort",
@@ -141,11 +366,45 @@ pub enum VisitorExternalTool<'data> {
 	RewriteJsAttr { attr: &'data str, code: &'data str },
 	RewriteHttpEquivContent(&'data str),
 	RewriteCss(&'data str),
+	RewriteInlineStyleAttr(&'data str),
 	GetScriptText { found_head: bool },
 	Log(&'data str),
 }
 
 impl<'alloc, 'data, T> Visitor<'alloc, 'data, T> {
+	/// Builds a `Visitor` from a raw, possibly non-UTF-8 HTML document: sniffs the source
+	/// encoding and decodes the whole document into UTF-8 once via [`decode_document`], allocates
+	/// the decoded buffer in `alloc` so it outlives the `Visitor`, then parses *that* buffer.
+	///
+	/// This is the only supported way to go from raw bytes to a `Visitor` — every
+	/// `calculate_bounds`/`boundaries` offset [`Visitor::rewrite`] computes is a UTF-8 byte
+	/// offset into the decoded buffer, never into `bytes` itself. The detected source encoding is
+	/// returned alongside the `Visitor` in case the caller needs to re-encode the rewritten
+	/// UTF-8 output back to it.
+	pub fn from_bytes(
+		alloc: &'alloc Allocator,
+		bytes: &[u8],
+		rules: &'data [RewriteRule<T>],
+		external_tool_func: &'data VisitorExternalToolCallback<T>,
+		rule_data: &'data T,
+		from_top: bool,
+	) -> (Self, &'static Encoding) {
+		let (decoded, encoding) = decode_document(bytes);
+		let data = alloc.alloc_str(&decoded);
+
+		let visitor = Self {
+			alloc,
+			rules,
+			external_tool_func,
+			rule_data,
+			data,
+			tree: tl::parse(data, tl::ParserOptions::default()),
+			from_top,
+		};
+
+		(visitor, encoding)
+	}
+
 	fn boundaries(&self, tag: &HTMLTag<'data>) -> Result<Span, RewriterError> {
 		let (start, end) = tag.boundaries(self.tree.parser());
 		let end = end + 1;
@@ -228,7 +487,22 @@ impl<'alloc, 'data, T> Visitor<'alloc, 'data, T> {
 				for (k, v) in tag.attributes().iter() {
 					let attr = k.try_as_utf8_str().ok_or(RewriterError::NotUtf8)?;
 
-					if let Some(cb) = self.check_rules(name, attr)
+					if (attr == "srcset" || attr == "imagesrcset")
+						&& let Some(cb) = self.check_rules(name, attr)
+						&& let Some(v) = v
+					{
+						let value = v.try_as_utf8_str().ok_or(RewriterError::NotUtf8)?;
+						let rewritten = rewrite_srcset(self.alloc, value, cb, self.rule_data)
+							.map_err(RewriterError::Rewrite)?;
+						let bounds = self.calculate_bounds(v)?;
+
+						if let Some(rewritten) = rewritten {
+							changes.add(HtmlRewrite::replace_attr(bounds, rewritten));
+						} else {
+							let key = self.calculate_bounds(k)?;
+							changes.add(HtmlRewrite::remove_attr(self.data, key, bounds));
+						}
+					} else if let Some(cb) = self.check_rules(name, attr)
 						&& let Some(v) = v
 					{
 						let value = v.try_as_utf8_str().ok_or(RewriterError::NotUtf8)?;
@@ -255,6 +529,18 @@ impl<'alloc, 'data, T> Visitor<'alloc, 'data, T> {
 						}
 					}
 
+					if attr == "style"
+						&& let Some(v) = v
+					{
+						let value = v.try_as_utf8_str().ok_or(RewriterError::NotUtf8)?;
+						let bounds = self.calculate_bounds(v)?;
+						let rewritten = self.external_tool_val(
+							VisitorExternalTool::RewriteInlineStyleAttr(value),
+						)?;
+
+						changes.add(HtmlRewrite::replace_attr(bounds, rewritten));
+					}
+
 					if EVENT_ATTRIBUTES.contains(&attr)
 						&& let Some(v) = v
 					{
@@ -316,28 +602,49 @@ impl<'alloc, 'data, T> Visitor<'alloc, 'data, T> {
 					));
 				}
 
-				if name == "meta"
-					&& let Some(Some(eqiv)) = tag.attributes().get(&"http-equiv".into())
-				{
-					let mut val = StringBuilder::from_str_in(
-						eqiv.try_as_utf8_str().ok_or(RewriterError::NotUtf8)?,
-						self.alloc,
-					);
-					val.as_mut_str().make_ascii_lowercase();
-
-					if val == "content-security-policy" {
-						changes.add(HtmlRewrite::remove_node(self.boundaries(tag)?));
-					} else if val == "refresh"
-						&& let Some(Some(content)) = tag.attributes().get(&"content".into())
-					{
-						let val = content.try_as_utf8_str().ok_or(RewriterError::NotUtf8)?;
-						let rewritten = self
-							.external_tool_val(VisitorExternalTool::RewriteHttpEquivContent(val))?;
+				if name == "meta" {
+					// The rewriter only ever operates on the decoded UTF-8 buffer, so the page's
+					// declared charset must be rewritten to match, or the client would try to
+					// decode already-UTF-8 output as the original (often non-UTF-8) charset.
+					if let Some(Some(charset)) = tag.attributes().get(&"charset".into()) {
 						changes.add(HtmlRewrite::replace_attr(
-							self.calculate_bounds(content)?,
-							rewritten,
+							self.calculate_bounds(charset)?,
+							"utf-8",
 						));
 					}
+
+					if let Some(Some(eqiv)) = tag.attributes().get(&"http-equiv".into()) {
+						let mut val = StringBuilder::from_str_in(
+							eqiv.try_as_utf8_str().ok_or(RewriterError::NotUtf8)?,
+							self.alloc,
+						);
+						val.as_mut_str().make_ascii_lowercase();
+
+						if val == "content-security-policy" {
+							changes.add(HtmlRewrite::remove_node(self.boundaries(tag)?));
+						} else if val == "refresh"
+							&& let Some(Some(content)) = tag.attributes().get(&"content".into())
+						{
+							let val = content.try_as_utf8_str().ok_or(RewriterError::NotUtf8)?;
+							let rewritten = self.external_tool_val(
+								VisitorExternalTool::RewriteHttpEquivContent(val),
+							)?;
+							changes.add(HtmlRewrite::replace_attr(
+								self.calculate_bounds(content)?,
+								rewritten,
+							));
+						} else if val == "content-type"
+							&& let Some(Some(content)) = tag.attributes().get(&"content".into())
+						{
+							let val = content.try_as_utf8_str().ok_or(RewriterError::NotUtf8)?;
+							if let Some(rewritten) = rewrite_charset_param(self.alloc, val) {
+								changes.add(HtmlRewrite::replace_attr(
+									self.calculate_bounds(content)?,
+									rewritten,
+								));
+							}
+						}
+					}
 				}
 			}
 		}
@@ -363,3 +670,145 @@ impl<'alloc, 'data, T> Visitor<'alloc, 'data, T> {
 		Ok(())
 	}
 }
+
+#[cfg(test)]
+mod tests {
+	use super::*;
+
+	#[test]
+	fn detect_encoding_prefers_bom() {
+		assert_eq!(
+			detect_encoding(b"\xEF\xBB\xBF<html></html>"),
+			encoding_rs::UTF_8
+		);
+		assert_eq!(
+			detect_encoding(b"\xFE\xFF<html></html>"),
+			encoding_rs::UTF_16BE
+		);
+		assert_eq!(
+			detect_encoding(b"\xFF\xFE<html></html>"),
+			encoding_rs::UTF_16LE
+		);
+	}
+
+	#[test]
+	fn detect_encoding_reads_meta_charset() {
+		let html = b"<html><head><meta charset=\"shift_jis\"></head></html>";
+		assert_eq!(detect_encoding(html), encoding_rs::SHIFT_JIS);
+	}
+
+	#[test]
+	fn detect_encoding_reads_http_equiv_content_type() {
+		let html = b"<html><head><meta http-equiv=\"Content-Type\" \
+			content=\"text/html; charset=EUC-KR\"></head></html>";
+		assert_eq!(detect_encoding(html), encoding_rs::EUC_KR);
+	}
+
+	#[test]
+	fn detect_encoding_ignores_charset_outside_meta_tags() {
+		let html = b"<html><head><script>var charset=foo;</script>\
+			<meta charset=\"gbk\"></head></html>";
+		assert_eq!(detect_encoding(html), encoding_rs::GBK);
+	}
+
+	#[test]
+	fn detect_encoding_defaults_to_windows_1252() {
+		let html = b"<html><body>plain text, no declared charset</body></html>";
+		assert_eq!(detect_encoding(html), encoding_rs::WINDOWS_1252);
+	}
+
+	#[test]
+	fn decode_document_decodes_non_utf8_bytes() {
+		let (encoded, _, _) = encoding_rs::WINDOWS_1252.encode("<p>caf\u{e9}</p>");
+		let (decoded, encoding) = decode_document(&encoded);
+
+		assert_eq!(decoded, "<p>café</p>");
+		assert_eq!(encoding, encoding_rs::WINDOWS_1252);
+	}
+
+	#[test]
+	fn decode_document_honours_bom_over_default() {
+		let (decoded, encoding) = decode_document("\u{feff}<p>hello</p>".as_bytes());
+
+		assert_eq!(decoded, "<p>hello</p>");
+		assert_eq!(encoding, encoding_rs::UTF_8);
+	}
+
+	#[test]
+	fn rewrite_charset_param_replaces_label() {
+		let alloc = Allocator::default();
+		let rewritten = rewrite_charset_param(&alloc, "text/html; charset=Shift_JIS").unwrap();
+
+		assert_eq!(rewritten, "text/html; charset=utf-8");
+	}
+
+	#[test]
+	fn rewrite_charset_param_without_charset_is_none() {
+		let alloc = Allocator::default();
+		assert!(rewrite_charset_param(&alloc, "text/html").is_none());
+	}
+
+	#[test]
+	fn parse_srcset_candidates_handles_plain_list() {
+		let candidates = parse_srcset_candidates("small.jpg 1x, large.jpg 2x");
+		assert_eq!(candidates, vec![("small.jpg", "1x"), ("large.jpg", "2x")]);
+	}
+
+	#[test]
+	fn parse_srcset_candidates_handles_width_descriptors_and_no_descriptor() {
+		let candidates = parse_srcset_candidates("a.jpg 480w, b.jpg, c.jpg 800w");
+		assert_eq!(
+			candidates,
+			vec![("a.jpg", "480w"), ("b.jpg", ""), ("c.jpg", "800w")]
+		);
+	}
+
+	#[test]
+	fn parse_srcset_candidates_keeps_comma_embedded_in_data_url() {
+		let candidates = parse_srcset_candidates(
+			"data:image/png;base64,AAAA== 1x, data:image/png;base64,BBBB== 2x",
+		);
+		assert_eq!(
+			candidates,
+			vec![
+				("data:image/png;base64,AAAA==", "1x"),
+				("data:image/png;base64,BBBB==", "2x"),
+			]
+		);
+	}
+
+	#[test]
+	fn parse_srcset_candidates_ignores_empty_and_trailing_commas() {
+		let candidates = parse_srcset_candidates(" , a.jpg 1x ,, b.jpg 2x , ");
+		assert_eq!(candidates, vec![("a.jpg", "1x"), ("b.jpg", "2x")]);
+	}
+
+	#[test]
+	fn rewrite_srcset_proxies_every_url() {
+		let alloc = Allocator::default();
+		let cb: RewriteRuleCallback<()> =
+			Box::new(|alloc, value, _| Ok(Some(alloc.alloc_str(&format!("/proxy/{value}")))));
+
+		let rewritten = rewrite_srcset(&alloc, "a.jpg 1x, b.jpg 2x", &cb, &())
+			.unwrap()
+			.unwrap();
+
+		assert_eq!(rewritten, "/proxy/a.jpg 1x, /proxy/b.jpg 2x");
+	}
+
+	#[test]
+	fn rewrite_srcset_drops_whole_attribute_when_a_candidate_is_disallowed() {
+		let alloc = Allocator::default();
+		let cb: RewriteRuleCallback<()> = Box::new(|alloc, value, _| {
+			if value == "blocked.jpg" {
+				Ok(None)
+			} else {
+				Ok(Some(alloc.alloc_str(&format!("/proxy/{value}"))))
+			}
+		});
+
+		let rewritten = rewrite_srcset(&alloc, "a.jpg 1x, blocked.jpg 2x", &cb, &()).unwrap();
+
+		assert!(rewritten.is_none());
+	}
+}